@@ -14,11 +14,29 @@ fn main() {
              .help("the befunge source file")
              .required(true)
              .index(1))
+        .arg(Arg::with_name("trace")
+             .long("trace")
+             .help("print an instruction trace as the program runs"))
+        .arg(Arg::with_name("break")
+             .long("break")
+             .value_name("X,Y")
+             .help("pause for interactive debugging when the cursor reaches X,Y")
+             .takes_value(true)
+             .number_of_values(1)
+             .multiple(true))
         .get_matches();
 
     let fname = args.value_of("SOURCE").unwrap();
+    let trace = args.is_present("trace");
+    let breakpoints = args.values_of("break")
+        .map(|vals| vals.filter_map(state::Location::parse).collect())
+        .unwrap_or_default();
+
     match state::State::new_from_file(fname).as_mut() {
-        Ok(state) => state.run(),
+        Ok(state) => {
+            state.enable_debug(trace, breakpoints);
+            state.run();
+        }
         Err(e) => eprintln!("Befunge error : {}", e),
     }
 
@@ -6,6 +6,7 @@ use std::io;
 use std::io::BufReader;
 use std::io::BufRead;
 use std::io::Result;
+use std::io::Write;
 use std::path::Path;
 use std::vec::Vec;
 
@@ -22,17 +23,19 @@ pub struct Location {
 }
 
 impl Location {
-    //step takes the location and moves in given a direction
-    pub fn step(&self, direction: Direction) -> Location {
+    // step takes the location and moves it one cell in the given direction,
+    // wrapping to the opposite edge of a row_width x height torus rather
+    // than falling off the bounded playfield
+    pub fn step(&self, direction: Direction, row_width: usize, height: usize) -> Location {
         let y = match direction {
-            Up => self.y.wrapping_sub(1),
-            Down => self.y.wrapping_add(1),
+            Up => if self.y == 0 { height.saturating_sub(1) } else { self.y - 1 },
+            Down => if self.y + 1 >= height { 0 } else { self.y + 1 },
             _ => self.y,
         };
 
         let x = match direction {
-            Right => self.x.wrapping_add(1),
-            Left => self.x.wrapping_sub(1),
+            Right => if self.x + 1 >= row_width { 0 } else { self.x + 1 },
+            Left => if self.x == 0 { row_width.saturating_sub(1) } else { self.x - 1 },
             _ => self.x,
         };
 
@@ -40,8 +43,8 @@ impl Location {
     }
 
     //step_mut destructively moves this location in the given direction
-    pub fn step_mut(&mut self, direction: Direction) -> Location {
-        let next = self.step(direction);
+    pub fn step_mut(&mut self, direction: Direction, row_width: usize, height: usize) -> Location {
+        let next = self.step(direction, row_width, height);
         self.x = next.x;
         self.y = next.y;
         *self
@@ -52,6 +55,14 @@ impl Location {
     pub fn new(x: usize, y: usize) -> Location {
         Location { x: x, y: y }
     }
+
+    // parse reads a "x,y" pair, as used on the command line for breakpoints
+    pub fn parse(s: &str) -> Option<Location> {
+        let mut parts = s.splitn(2, ',');
+        let x = parts.next()?.trim().parse::<usize>().ok()?;
+        let y = parts.next()?.trim().parse::<usize>().ok()?;
+        Some(Location::new(x, y))
+    }
 }
 
 // Direction represents a specific direction for the interpreter to go
@@ -112,7 +123,7 @@ impl Default for Mode {
 }
 
 // A covenience wrapper around a Vec to produce a stack
-#[derive(Default)]
+#[derive(Default, Clone)]
 struct Stack {
     stack: Vec<i64>,
 }
@@ -144,16 +155,98 @@ impl Stack {
     }
 }
 
+// Frame pairs one stack in the stack-of-stacks with the storage offset that
+// was active while it was the TOSS, so `}` can restore it when the frame ends
+#[derive(Default, Clone)]
+struct Frame {
+    stack: Stack,
+    storage_offset: Location,
+}
+
+// Ip is a single Funge-98 instruction pointer: its own cursor, direction,
+// execution mode and stack-of-stacks, so several can walk the same playfield
+// at once, each with its own scoped local stacks
+#[derive(Clone)]
+struct Ip {
+    frames: Vec<Frame>, // the stack-of-stacks; frames.last() is the TOSS
+    cursor: Location, // the instruction pointer that shows where we are in the grid
+    direction: Direction, // the direction in which the cursor is moving
+    execution_mode: Mode, // what execution mode is the interpreter currently in
+    overlays: HashMap<char, Vec<fn(&mut State)>>, // fingerprint bindings loaded onto A-Z, a stack per letter so nesting restores the previous meaning
+}
+
+impl Default for Ip {
+    fn default() -> Ip {
+        Ip {
+            frames: vec![Frame::default()],
+            cursor: Default::default(),
+            direction: Default::default(),
+            execution_mode: Default::default(),
+            overlays: HashMap::new(),
+        }
+    }
+}
+
+impl Ip {
+    // new creates a fresh instruction pointer at the grid origin
+    fn new() -> Ip {
+        Default::default()
+    }
+
+    // split produces a child ip that shares a copy of this ip's stack-of-stacks
+    // and turns around to walk back the way it came
+    fn split(&self) -> Ip {
+        let mut child = self.clone();
+        child.direction = self.direction.opposite();
+        child
+    }
+
+    // toss_mut mutably borrows the top stack of the stack-of-stacks
+    fn toss_mut(&mut self) -> &mut Stack {
+        &mut self.frames.last_mut().unwrap().stack
+    }
+
+    // storage_offset returns the storage offset active for the current TOSS
+    // frame, which `p`/`g` add to their coordinates
+    fn storage_offset(&self) -> Location {
+        self.frames.last().unwrap().storage_offset
+    }
+
+    // overlay_binding looks up the currently active fingerprint binding for
+    // an uppercase letter, if any fingerprint has rebound it
+    fn overlay_binding(&self, ch: char) -> Option<fn(&mut State)> {
+        self.overlays.get(&ch).and_then(|bindings| bindings.last().cloned())
+    }
+}
+
 
 // State represents the current state of the map
-#[derive(Default)]
 pub struct State {
     initial_grid: Vec<Vec<char>>, // the state of the grid when loaded from the file
     grid_updates: HashMap<Location, char>, // any updates to the grid from runtime
-    stack: Stack, // the current stack of values pushed from execution
-    cursor: Location, // the instruction pointer that shows where we are in the grid
-    direction: Direction, // the direction in which the cursor is moving
-    execution_mode: Mode, // what execution mode is the interpreter currently in
+    ips: Vec<Ip>, // every live instruction pointer, run round-robin one tick at a time
+    current: usize, // index into ips of the ip currently being executed
+    width: usize, // logical width of the bounding box, for torus wrapping
+    height: usize, // logical height of the bounding box, for torus wrapping
+    trace: bool, // when true, print an instruction-trace row before every step
+    breakpoints: Vec<Location>, // coordinates that drop into the interactive debugger
+    step_count: u64, // total instructions executed so far, shown in the trace STEP column
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            initial_grid: Vec::new(),
+            grid_updates: HashMap::new(),
+            ips: vec![Ip::new()],
+            current: 0,
+            width: 0,
+            height: 0,
+            trace: false,
+            breakpoints: Vec::new(),
+            step_count: 0,
+        }
+    }
 }
 
 impl State {
@@ -175,29 +268,64 @@ impl State {
             }
             state.initial_grid.push(vec)
         }
+        state.height = state.initial_grid.len();
+        state.width = state.initial_grid.iter().map(|row| row.len()).max().unwrap_or(0);
         Ok(state)
     }
 
-    // value_at - returns the value at a given location
+    // enable_debug turns on the instruction-trace table and/or coordinate
+    // breakpoints that drop into an interactive prompt during run
+    pub fn enable_debug(&mut self, trace: bool, breakpoints: Vec<Location>) {
+        self.trace = trace;
+        self.breakpoints = breakpoints;
+    }
+
+    // ip borrows the instruction pointer currently being executed
+    fn ip(&self) -> &Ip {
+        &self.ips[self.current]
+    }
+
+    // ip_mut mutably borrows the instruction pointer currently being executed
+    fn ip_mut(&mut self) -> &mut Ip {
+        &mut self.ips[self.current]
+    }
+
+    // stack mutably borrows the TOSS of the instruction pointer currently being executed
+    fn stack(&mut self) -> &mut Stack {
+        self.ip_mut().toss_mut()
+    }
+
+    // value_at - returns the value at a given location, treating any cell
+    // inside the bounding box as a space when the source line ran out early
     pub fn value_at(&self, loc: Location) -> Option<char> {
         if self.grid_updates.contains_key(&loc) {
             return Some(self.grid_updates[&loc]);
         }
 
-        if self.initial_grid.len() >= loc.y {
+        if loc.y >= self.height || loc.x >= self.width {
             return None;
         }
 
-        if self.initial_grid[loc.y].len() >= loc.x {
-            return None;
-        }
+        Some(self.initial_grid[loc.y].get(loc.x).cloned().unwrap_or(' '))
+    }
+
+    // row_width returns the number of populated columns in a row, falling
+    // back to the overall bounding box width for blank or out-of-range rows
+    fn row_width(&self, y: usize) -> usize {
+        let len = self.initial_grid.get(y).map(|row| row.len()).unwrap_or(0);
+        if len == 0 { self.width } else { len }
+    }
 
-        Some(self.initial_grid[loc.y][loc.x])
+    // wrapped_location reduces a pair of signed stack coordinates to an
+    // in-bounds Location, wrapping x by the bounding box width and y by its
+    // height, so writes/reads beyond the loaded source stay deterministic
+    fn wrapped_location(&self, x: i64, y: i64) -> Location {
+        Location::new(wrap_coord(x, self.width), wrap_coord(y, self.height))
     }
 
     // current_value - gets the value at the current position
     pub fn current_value(&self) -> Option<char> {
-        self.value_at(self.cursor.clone())
+        self.value_at(self.ip().cursor)
     }
 
     // set_value - sets the value at a given location
@@ -205,51 +333,55 @@ impl State {
         self.grid_updates.insert(loc, ch);
     }
 
-    // increment_cursor moves our cursor to the next position - does not wrap
+    // increment_cursor moves our cursor to the next position, wrapping
+    // around the torus described by the current row's width and the
+    // playfield's height
     fn increment_cursor(&mut self) -> Location {
-        self.cursor.step_mut(self.direction)
+        let direction = self.ip().direction;
+        let row_width = self.row_width(self.ip().cursor.y);
+        let height = self.height;
+        self.ip_mut().cursor.step_mut(direction, row_width, height)
     }
 
-    // next_value increments the cursor and produces the next value if there is one
+    // next_value increments the cursor and produces the value now under it
     fn next_value(&mut self) -> Option<char> {
-        self.step_cursor();
+        self.increment_cursor();
         self.current_value()
     }
 
-    // step_cursor moves the cursor to the next valid space
+    // step_cursor moves the cursor to the next cell on the torus
     pub fn step_cursor(&mut self) -> bool {
-        if self.execution_mode == Mode::Exited {
+        if self.ip().execution_mode == Mode::Exited {
             return false;
         }
 
-        let start = self.cursor;
-        while self.next_value().is_none() {
-            if start == self.cursor {
-                return false;
-            }
-        }
-
+        self.next_value();
         true
     }
 
     // process_quoted reads the current character and pushes it onto the stack if needed
     pub fn process_quoted(&mut self, ch: char) {
         if ch == '"' {
-            self.execution_mode = Mode::Normal;
+            self.ip_mut().execution_mode = Mode::Normal;
             return;
         }
-        self.stack.push(util::char_to_i64(ch));
+        self.stack().push(util::char_to_i64(ch));
     }
 
 
     // process_normal reads the current character and process it according to normal rules
     pub fn process_normal(&mut self, ch: char) {
+        if let Some(binding) = self.ip().overlay_binding(ch) {
+            binding(self);
+            return;
+        }
+
         match ch {
             // push digits to the stack
             '0'...'9' | 'a'...'f' => push_digit(self, ch),
 
             // modal operators
-            '"' => self.execution_mode = Mode::Quoted,
+            '"' => self.ip_mut().execution_mode = Mode::Quoted,
             '#' => {self.step_cursor();},
 
             // arithmetic operators
@@ -264,20 +396,20 @@ impl State {
             '`' => greater_than(self),
 
             // directional operations
-            '>' => self.direction = Right,
-            '<' => self.direction = Left,
-            '^' => self.direction = Up,
-            'v' => self.direction = Down,
-            '?' => self.direction = rand::random::<Direction>(),
+            '>' => self.ip_mut().direction = Right,
+            '<' => self.ip_mut().direction = Left,
+            '^' => self.ip_mut().direction = Up,
+            'v' => self.ip_mut().direction = Down,
+            '?' => self.ip_mut().direction = rand::random::<Direction>(),
 
             // branching operators
             '|' => veritical_if(self),
             '_' => horizontal_if(self),
 
             // stack manipulation operators
-            '$' => {self.stack.pop();},
-            ':' => self.stack.duplicate_top(),
-            '\\' => self.stack.swap_top(),
+            '$' => {self.stack().pop();},
+            ':' => self.stack().duplicate_top(),
+            '\\' => self.stack().swap_top(),
 
             // input operators
             '&' => read_integer(self),
@@ -291,6 +423,17 @@ impl State {
             'p' => put(self),
             'g' => get(self),
 
+            // concurrency operators
+            't' => split(self),
+
+            // stack-stack operators
+            '{' => begin_block(self),
+            '}' => end_block(self),
+            'u' => stack_under_stack(self),
+
+            // fingerprint operators
+            '(' => load_fingerprint(self),
+            ')' => unload_fingerprint(self),
 
             // end the program
             '@' => end_program(self),
@@ -298,6 +441,164 @@ impl State {
         };
 
     }
+
+    // step executes a single instruction for the ip currently being executed,
+    // then advances its cursor to the next cell
+    fn step(&mut self) {
+        let ch = self.current_value().unwrap_or(' ');
+
+        match self.ip().execution_mode {
+            Mode::Quoted => self.process_quoted(ch),
+            Mode::Normal => self.process_normal(ch),
+            Mode::Exited => return,
+        }
+
+        if self.ip().execution_mode != Mode::Exited {
+            self.step_cursor();
+        }
+    }
+
+    // tick runs every currently live ip through a single instruction, round-robin
+    fn tick(&mut self) {
+        let live = self.ips.len();
+        for i in 0..live {
+            self.current = i;
+            if self.ip().execution_mode == Mode::Exited {
+                continue;
+            }
+
+            self.step_count += 1;
+            let at_breakpoint = self.breakpoints.contains(&self.ip().cursor);
+            if self.trace && !at_breakpoint {
+                self.print_trace_row();
+            }
+            if at_breakpoint {
+                self.debug_prompt();
+            }
+
+            self.step();
+        }
+        self.ips.retain(|ip| ip.execution_mode != Mode::Exited);
+        self.current = 0;
+    }
+
+    // run executes the program until every ip has reached Mode::Exited
+    pub fn run(&mut self) {
+        if self.trace {
+            self.print_trace_header();
+        }
+        while !self.ips.is_empty() {
+            self.tick();
+        }
+    }
+
+    // print_trace_header writes the column headings for the instruction trace
+    fn print_trace_header(&self) {
+        println!("{:>6} | {:^9} | {:<5} | {:<4} | STACK",
+                  "STEP", "POSITION", "DIRECTION", "INSN");
+    }
+
+    // print_trace_row writes one disassembly-style row for the instruction
+    // about to execute on the current ip
+    fn print_trace_row(&self) {
+        let cursor = self.ip().cursor;
+        let direction = self.ip().direction;
+        let ch = self.current_value().unwrap_or(' ');
+        println!("{:>6} | ({:>3},{:>3}) | {:<9} | {:<4} | {}",
+                  self.step_count,
+                  cursor.x,
+                  cursor.y,
+                  direction_name(direction),
+                  ch,
+                  self.render_stack());
+    }
+
+    // render_stack formats every frame of the current ip's stack-of-stacks
+    // for the trace table, TOSS first, so frames pushed by `{` stay visible
+    // instead of looking like data went missing
+    fn render_stack(&self) -> String {
+        self.ip()
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| format!("{:?}", frame.stack.stack))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    // debug_prompt pauses at a breakpoint and reads interactive debugger
+    // commands from stdin: enter single-steps, `c` continues, `s` prints the
+    // full stack, and `peek`/`poke` inspect or mutate a grid cell
+    fn debug_prompt(&mut self) {
+        loop {
+            self.print_trace_row();
+            print!("debug> ");
+            let _ = io::stdout().flush();
+
+            let mut input = String::new();
+            match io::stdin().read_line(&mut input) {
+                Ok(0) => return, // end of input (piped stdin, or Ctrl+D)
+                Ok(_) => (),
+                Err(_) => return,
+            }
+
+            match input.trim() {
+                "" => {
+                    self.step();
+                    self.step_count += 1;
+                }
+                "c" => return,
+                "s" => {
+                    for (depth, frame) in self.ip().frames.iter().rev().enumerate() {
+                        println!("stack[{}]: {:?}", depth, frame.stack.stack);
+                    }
+                }
+                line => {
+                    if let Some(args) = line.strip_prefix("peek ") {
+                        self.debug_peek(args);
+                    } else if let Some(args) = line.strip_prefix("poke ") {
+                        self.debug_poke(args);
+                    } else {
+                        println!("unknown debug command: {}", line);
+                    }
+                }
+            }
+        }
+    }
+
+    // debug_peek prints the grid value at a "x,y" coordinate
+    fn debug_peek(&self, args: &str) {
+        match Location::parse(args) {
+            Some(loc) => println!("({}, {}) = {:?}", loc.x, loc.y, self.value_at(loc).unwrap_or(' ')),
+            None => println!("usage: peek x,y"),
+        }
+    }
+
+    // debug_poke writes a single character to the grid at "x,y,c"
+    fn debug_poke(&mut self, args: &str) {
+        let parts: Vec<&str> = args.splitn(3, ',').collect();
+        let parsed = match parts.as_slice() {
+            [x, y, ch] => x.trim().parse::<usize>().ok()
+                .and_then(|x| y.trim().parse::<usize>().ok().map(|y| (x, y)))
+                .and_then(|(x, y)| ch.trim().chars().next().map(|c| (x, y, c))),
+            _ => None,
+        };
+
+        match parsed {
+            Some((x, y, c)) => self.set_value(Location::new(x, y), c),
+            None => println!("usage: poke x,y,c"),
+        }
+    }
+}
+
+// direction_name renders a Direction for the instruction-trace table
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Up => "Up",
+        Down => "Down",
+        Left => "Left",
+        Right => "Right",
+    }
 }
 
 // read_char reads a character from the user
@@ -309,7 +610,7 @@ fn read_char(state : &mut State) {
             ch = c;
         }
     }
-    state.stack.push(util::char_to_i64(ch));
+    state.stack().push(util::char_to_i64(ch));
 }
 
 // read_integer reads a number from the commandline
@@ -317,115 +618,334 @@ fn read_integer(state : &mut State) {
     let mut input = String::new();
     if let Ok(_) = io::stdin().read_line(&mut input) {
         if let Ok(value) = input.trim().parse::<i64>() {
-            state.stack.push(value);
+            state.stack().push(value);
             return;
         }
     }
-    state.stack.push(0);
+    state.stack().push(0);
 }
 
-// put pops the values y, x, and v and stores value v at location {x,y}
+// put pops the values y, x, and v and stores value v at location {x,y} plus
+// the active storage offset, wrapping the coordinates onto the torus so
+// out-of-bounds writes still land somewhere deterministic
 fn put(state: &mut State) {
-    let y = state.stack.pop();
-    let x = state.stack.pop();
-    let v = state.stack.pop();
-    state.set_value(Location{x : x as usize, y : y as usize }, util::i64_to_char(v));
+    let y = state.stack().pop();
+    let x = state.stack().pop();
+    let v = state.stack().pop();
+    let offset = state.ip().storage_offset();
+    let loc = state.wrapped_location(x + offset.x as i64, y + offset.y as i64);
+    state.set_value(loc, util::i64_to_char(v));
 }
 
-// get puts the value at {x, y} onto the stack
+// get puts the value at {x, y} plus the active storage offset onto the
+// stack, wrapping the coordinates the same way put does
 fn get(state: &mut State) {
-    let y = state.stack.pop();
-    let x = state.stack.pop();
-    let v = util::char_to_i64(state.value_at(Location{x : x as usize, y : y as usize}).unwrap_or(0 as char));
+    let y = state.stack().pop();
+    let x = state.stack().pop();
+    let offset = state.ip().storage_offset();
+    let loc = state.wrapped_location(x + offset.x as i64, y + offset.y as i64);
+    let v = util::char_to_i64(state.value_at(loc).unwrap_or(' '));
+    state.stack().push(v);
+}
+
+// wrap_coord reduces a signed coordinate into the half-open range [0, bound),
+// wrapping negative or overflowing values back onto the bounded playfield
+fn wrap_coord(value: i64, bound: usize) -> usize {
+    if bound == 0 {
+        return 0;
+    }
+    let bound = bound as i64;
+    (((value % bound) + bound) % bound) as usize
 }
 
 // greater_than tests if b > a
 fn greater_than(state : &mut State) {
-    let a = state.stack.pop();
-    let b = state.stack.pop();
+    let a = state.stack().pop();
+    let b = state.stack().pop();
     let mut result = 0;
     if b > a {
         result = 1;
     }
-    state.stack.push(result);
+    state.stack().push(result);
 }
 
 // logical_negation tests if the top most value is 0
 fn logical_negation(state : &mut State) {
     let mut negation = 0;
-    if state.stack.pop() == 0 {
+    if state.stack().pop() == 0 {
         negation = 1;
     }
-    state.stack.push(negation);
+    state.stack().push(negation);
 }
 
 // print_digit prints a number from the stack
 fn print_digit(state: &mut State) {
-    print!("{}", state.stack.pop());
+    print!("{}", state.stack().pop());
 }
 
 // print_char prints the top value on the stack as a char
 fn print_char(state: &mut State) {
-    print!("{}", util::i64_to_char(state.stack.pop()));
+    print!("{}", util::i64_to_char(state.stack().pop()));
 }
 
 // push_digit takes a character and pushs it onto the stack as a digit
 fn push_digit(state: &mut State, ch: char) {
     if let Some(v) = ch.to_digit(16) {
-        state.stack.push(v as i64);
+        state.stack().push(v as i64);
     }
 }
 
 // addition performs addition on the two top values from the stack
 fn addition(state: &mut State) {
-    let sum = state.stack.pop() + state.stack.pop();
-    state.stack.push(sum);
+    let sum = state.stack().pop() + state.stack().pop();
+    state.stack().push(sum);
 }
 
 // subtraction performs b - a where a is popped from the stack before b
 fn subtraction(state: &mut State) {
-    let a = state.stack.pop();
-    let b = state.stack.pop();
-    state.stack.push(b - a);
+    let a = state.stack().pop();
+    let b = state.stack().pop();
+    state.stack().push(b - a);
 }
 
 // multiply performs a * b by popping two values from the stack
 fn multiply(state: &mut State) {
-    let product = state.stack.pop() * state.stack.pop();
-    state.stack.push(product);
+    let product = state.stack().pop() * state.stack().pop();
+    state.stack().push(product);
 }
 
 // divide performs b / a => does NOT user prompt
 fn divide(state: &mut State) {
-    let a = state.stack.pop();
-    let b = state.stack.pop();
-    state.stack.push(b / a);
+    let a = state.stack().pop();
+    let b = state.stack().pop();
+    state.stack().push(b / a);
 }
 
 // modulo performs b % a
 fn modulo(state: &mut State) {
-    let a = state.stack.pop();
-    let b = state.stack.pop();
-    state.stack.push(b % a);
+    let a = state.stack().pop();
+    let b = state.stack().pop();
+    state.stack().push(b % a);
 }
 
-// end_program sets the program to a terminated state
+// end_program sets the current ip to a terminated state - it alone stops running
 fn end_program(state: &mut State) {
-    state.execution_mode = Mode::Exited;
+    state.ip_mut().execution_mode = Mode::Exited;
+}
+
+// split clones the current ip in place: the child turns 180 degrees and
+// inherits a copy of the parent's stack, so both continue independently.
+// The child's cursor is stepped off the `t` cell the same way step() steps
+// the parent's, so it doesn't wake up on its next turn and re-execute `t`.
+fn split(state: &mut State) {
+    let mut child = state.ip().split();
+    let cursor = child.cursor;
+    let row_width = state.row_width(cursor.y);
+    let height = state.height;
+    child.cursor = cursor.step(child.direction, row_width, height);
+    state.ips.push(child);
+}
+
+// reflect reverses the current ip's direction, Funge-98's way of signalling
+// that an operator could not be completed
+fn reflect(state: &mut State) {
+    let direction = state.ip().direction;
+    state.ip_mut().direction = direction.opposite();
+}
+
+// begin_block implements `{`: pop a signed count n, push a fresh stack that
+// becomes the TOSS, transfer n cells down from the old stack preserving
+// order (or push |n| zeros onto the old stack if n is negative), and give
+// the new frame a storage offset of the cell just past this instruction
+fn begin_block(state: &mut State) {
+    let n = state.stack().pop();
+    let direction = state.ip().direction;
+    let cursor = state.ip().cursor;
+    let row_width = state.row_width(cursor.y);
+    let height = state.height;
+    let storage_offset = cursor.step(direction, row_width, height);
+
+    let mut new_stack = Stack::default();
+    if n >= 0 {
+        let mut transferred = Vec::new();
+        for _ in 0..n {
+            transferred.push(state.stack().pop());
+        }
+        for v in transferred.into_iter().rev() {
+            new_stack.push(v);
+        }
+    } else {
+        for _ in 0..n.unsigned_abs() {
+            state.stack().push(0);
+        }
+    }
+
+    state.ip_mut().frames.push(Frame { stack: new_stack, storage_offset });
+}
+
+// end_block implements `}`: pop n, discard the TOSS and restore the storage
+// offset beneath it, transferring n cells back down to the parent stack (or
+// removing |n| cells from it if n is negative); reflects if there is no
+// second stack to fall back to
+fn end_block(state: &mut State) {
+    let n = state.stack().pop();
+
+    if state.ip().frames.len() < 2 {
+        reflect(state);
+        return;
+    }
+
+    let toss_frame = state.ip_mut().frames.pop().unwrap();
+
+    if n >= 0 {
+        let mut toss_stack = toss_frame.stack;
+        let mut transferred = Vec::new();
+        for _ in 0..n {
+            transferred.push(toss_stack.pop());
+        }
+        for v in transferred.into_iter().rev() {
+            state.stack().push(v);
+        }
+    } else {
+        for _ in 0..n.unsigned_abs() {
+            state.stack().pop();
+        }
+    }
+}
+
+// stack_under_stack implements `u`: pop a signed count n and move that many
+// cells between the TOSS and the stack beneath it - from SOSS to TOSS if n
+// is positive, from TOSS to SOSS if negative; reflects if there is no SOSS
+fn stack_under_stack(state: &mut State) {
+    let n = state.stack().pop();
+
+    if state.ip().frames.len() < 2 {
+        reflect(state);
+        return;
+    }
+
+    let top = state.ip().frames.len() - 1;
+    if n >= 0 {
+        for _ in 0..n {
+            let v = state.ip_mut().frames[top - 1].stack.pop();
+            state.ip_mut().frames[top].stack.push(v);
+        }
+    } else {
+        for _ in 0..n.unsigned_abs() {
+            let v = state.ip_mut().frames[top].stack.pop();
+            state.ip_mut().frames[top - 1].stack.push(v);
+        }
+    }
 }
 
 // horizontal_if calculates a vertical branch
 fn horizontal_if(state: &mut State) {
-    state.direction = Left;
-    if state.stack.pop() == 0 {
-        state.direction = Right;
+    state.ip_mut().direction = Left;
+    if state.stack().pop() == 0 {
+        state.ip_mut().direction = Right;
     }
 }
 
 // veritical_if calculates a horizontal branch
 fn veritical_if(state: &mut State) {
-    state.direction = Up;
-    if state.stack.pop() == 0 {
-        state.direction = Down;
+    state.ip_mut().direction = Up;
+    if state.stack().pop() == 0 {
+        state.ip_mut().direction = Down;
+    }
+}
+
+// fingerprint_id folds a fingerprint name's bytes into the same numeric id
+// `(`/`)` compute from the values pushed and popped for it, most significant
+// character first
+fn fingerprint_id(name: &str) -> i64 {
+    name.chars().fold(0i64, |acc, c| acc.wrapping_mul(256).wrapping_add(c as i64))
+}
+
+// fingerprint_bindings returns the A-Z rebindings for a known fingerprint id,
+// or None if the id names a fingerprint this interpreter doesn't ship
+fn fingerprint_bindings(id: i64) -> Option<Vec<(char, fn(&mut State))>> {
+    if id == fingerprint_id("MATH") {
+        return Some(vec![
+            ('S', fp_sqrt as fn(&mut State)),
+            ('P', fp_pow as fn(&mut State)),
+            ('A', fp_abs as fn(&mut State)),
+            ('Z', fp_sign as fn(&mut State)),
+        ]);
+    }
+
+    None
+}
+
+// pop_fingerprint_id pops n values and combines them into a fingerprint id,
+// undoing the most-significant-character-first encoding used to load it
+fn pop_fingerprint_id(state: &mut State) -> i64 {
+    let n = state.stack().pop();
+    let mut values = Vec::new();
+    for _ in 0..n {
+        values.push(state.stack().pop());
+    }
+    values.reverse();
+    values.iter().fold(0i64, |acc, v| acc.wrapping_mul(256).wrapping_add(*v))
+}
+
+// load_fingerprint implements `(`: pop a count and that many values, combine
+// them into a fingerprint id, and rebind its letters on top of this ip's
+// overlay stack until a matching `)` unloads it; unknown ids reflect the ip
+fn load_fingerprint(state: &mut State) {
+    let id = pop_fingerprint_id(state);
+    match fingerprint_bindings(id) {
+        Some(bindings) => {
+            for (letter, func) in bindings {
+                state.ip_mut().overlays.entry(letter).or_default().push(func);
+            }
+        }
+        None => reflect(state),
     }
 }
+
+// unload_fingerprint implements `)`: pop a count and that many values,
+// combine them into a fingerprint id, and pop its letters off this ip's
+// overlay stack, restoring whatever meaning they had before it was loaded
+fn unload_fingerprint(state: &mut State) {
+    let id = pop_fingerprint_id(state);
+    match fingerprint_bindings(id) {
+        Some(bindings) => {
+            for (letter, _) in bindings {
+                if let Some(stack) = state.ip_mut().overlays.get_mut(&letter) {
+                    stack.pop();
+                }
+            }
+        }
+        None => reflect(state),
+    }
+}
+
+// fp_sqrt implements the MATH fingerprint's S: integer square root of the top value
+fn fp_sqrt(state: &mut State) {
+    let v = state.stack().pop();
+    let result = if v < 0 { 0 } else { (v as f64).sqrt() as i64 };
+    state.stack().push(result);
+}
+
+// fp_pow implements the MATH fingerprint's P: b raised to the power a,
+// wrapping on overflow rather than panicking
+fn fp_pow(state: &mut State) {
+    let a = state.stack().pop();
+    let b = state.stack().pop();
+    let exponent = if a < 0 { 0 } else { a as u32 };
+    state.stack().push(b.wrapping_pow(exponent));
+}
+
+// fp_abs implements the MATH fingerprint's A: absolute value of the top
+// value, wrapping on overflow rather than panicking (i64::MIN has no
+// positive representation, so it wraps back to itself)
+fn fp_abs(state: &mut State) {
+    let v = state.stack().pop();
+    state.stack().push(v.wrapping_abs());
+}
+
+// fp_sign implements the MATH fingerprint's Z: sign of the top value, -1/0/1
+fn fp_sign(state: &mut State) {
+    let v = state.stack().pop();
+    state.stack().push(v.signum());
+}